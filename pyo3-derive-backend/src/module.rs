@@ -9,6 +9,23 @@ use proc_macro2::{Span, TokenStream};
 use quote::quote;
 use syn;
 
+/// Generates the module initialization function, taking care of any `#[pyfn(...)]`
+/// functions declared inside it.
+pub fn process_module(
+    func: &mut syn::ItemFn,
+    fnname: &syn::Ident,
+    name: &syn::Ident,
+    doc: syn::Lit,
+    py3: bool,
+) -> syn::Result<TokenStream> {
+    process_functions_in_module(func)?;
+    Ok(if py3 {
+        py3_init(fnname, name, doc)
+    } else {
+        py2_init(fnname, name, doc)
+    })
+}
+
 /// Generates the function that is called by the python interpreter to initialize the native
 /// module
 pub fn py3_init(fnname: &syn::Ident, name: &syn::Ident, doc: syn::Lit) -> TokenStream {
@@ -38,15 +55,15 @@ pub fn py2_init(fnname: &syn::Ident, name: &syn::Ident, doc: syn::Lit) -> TokenS
 }
 
 /// Finds and takes care of the #[pyfn(...)] in `#[pymodule]`
-pub fn process_functions_in_module(func: &mut syn::ItemFn) {
+pub fn process_functions_in_module(func: &mut syn::ItemFn) -> syn::Result<()> {
     let mut stmts: Vec<syn::Stmt> = Vec::new();
 
     for stmt in func.block.stmts.iter_mut() {
         if let syn::Stmt::Item(syn::Item::Fn(ref mut func)) = stmt {
             if let Some((module_name, python_name, pyfn_attrs)) =
-                extract_pyfn_attrs(&mut func.attrs)
+                extract_pyfn_attrs(&mut func.attrs)?
             {
-                let function_to_python = add_fn_to_module(func, &python_name, pyfn_attrs);
+                let function_to_python = add_fn_to_module(func, &python_name, pyfn_attrs)?;
                 let function_wrapper_ident = function_wrapper_ident(&func.ident);
                 let item: syn::ItemFn = syn::parse_quote! {
                     fn block_wrapper() {
@@ -55,22 +72,106 @@ pub fn process_functions_in_module(func: &mut syn::ItemFn) {
                     }
                 };
                 stmts.extend(item.block.stmts.into_iter());
+            } else if let Some(parent) = extract_pymodule_submodule_attr(&mut func.attrs)? {
+                // `process_submodule` already embeds the child function inside the generated
+                // `__pyo3_make_submodule_*` helper, so the original top-level copy must not be
+                // kept around too - it would otherwise become a dead, unused sibling item.
+                stmts.extend(process_submodule(&parent, func)?.into_iter());
+                continue;
             }
         };
         stmts.push(stmt.clone());
     }
 
     func.block.stmts = stmts;
+    Ok(())
+}
+
+/// Extracts the parent module identifier from a `#[pymodule(parent)]` attribute on a function
+/// nested inside a `#[pymodule]`, marking it as a submodule of `parent`.
+fn extract_pymodule_submodule_attr(
+    attrs: &mut Vec<syn::Attribute>,
+) -> syn::Result<Option<syn::Ident>> {
+    let mut new_attrs = Vec::new();
+    let mut parent = None;
+
+    for attr in attrs.iter() {
+        match attr.interpret_meta() {
+            Some(syn::Meta::List(ref list)) if list.ident == "pymodule" => {
+                match list.nested.iter().next() {
+                    Some(syn::NestedMeta::Meta(syn::Meta::Word(ref ident))) => {
+                        parent = Some(ident.clone());
+                    }
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            list,
+                            "expected `#[pymodule(parent)]`",
+                        ));
+                    }
+                }
+            }
+            _ => new_attrs.push(attr.clone()),
+        }
+    }
+
+    *attrs = new_attrs;
+    Ok(parent)
+}
+
+/// Generates the statements that build a submodule and attach it to `parent`.
+fn process_submodule(parent: &syn::Ident, func: &mut syn::ItemFn) -> syn::Result<Vec<syn::Stmt>> {
+    process_functions_in_module(func)?;
+
+    let child = &func.ident;
+    let make_submodule_ident = syn::Ident::new(
+        &format!("__pyo3_make_submodule_{}", child),
+        Span::call_site(),
+    );
+
+    let item: syn::ItemFn = syn::parse_quote! {
+        fn block_wrapper() {
+            fn #make_submodule_ident(
+                py: ::pyo3::Python,
+                #parent: &::pyo3::types::PyModule,
+            ) -> ::pyo3::PyResult<()> {
+                #func
+
+                let child_module = ::pyo3::types::PyModule::new(py, stringify!(#child))?;
+                #child(py, child_module)?;
+
+                let dotted_name = format!("{}.{}", #parent.name()?, stringify!(#child));
+                child_module.setattr("__name__", &dotted_name)?;
+                py.import("sys")?
+                    .getattr("modules")?
+                    .downcast_ref::<::pyo3::types::PyDict>()?
+                    .set_item(&dotted_name, child_module)?;
+
+                #parent.add(stringify!(#child), child_module)?;
+                Ok(())
+            }
+            #make_submodule_ident(py, #parent)?;
+        }
+    };
+
+    Ok(item.block.stmts)
 }
 
 /// Transforms a rust fn arg parsed with syn into a method::FnArg
-fn wrap_fn_argument<'a>(input: &'a syn::FnArg, name: &'a syn::Ident) -> Option<method::FnArg<'a>> {
+fn wrap_fn_argument<'a>(
+    input: &'a syn::FnArg,
+    name: &'a syn::Ident,
+) -> syn::Result<Option<method::FnArg<'a>>> {
     match input {
-        &syn::FnArg::SelfRef(_) | &syn::FnArg::SelfValue(_) => None,
+        &syn::FnArg::SelfRef(_) | &syn::FnArg::SelfValue(_) => Ok(None),
         &syn::FnArg::Captured(ref cap) => {
             let (mutability, by_ref, ident) = match cap.pat {
                 syn::Pat::Ident(ref patid) => (&patid.mutability, &patid.by_ref, &patid.ident),
-                _ => panic!("unsupported argument: {:?}", cap.pat),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        &cap.pat,
+                        format!("unsupported argument: {:?}", cap.pat),
+                    ));
+                }
             };
 
             let py = match cap.ty {
@@ -84,7 +185,7 @@ fn wrap_fn_argument<'a>(input: &'a syn::FnArg, name: &'a syn::Ident) -> Option<m
             };
 
             let opt = method::check_arg_ty_and_optional(&name, &cap.ty);
-            Some(method::FnArg {
+            Ok(Some(method::FnArg {
                 name: ident,
                 mutability,
                 by_ref,
@@ -92,17 +193,23 @@ fn wrap_fn_argument<'a>(input: &'a syn::FnArg, name: &'a syn::Ident) -> Option<m
                 optional: opt,
                 py,
                 reference: method::is_ref(&name, &cap.ty),
-            })
+            }))
         }
-        &syn::FnArg::Ignored(_) => panic!("ignored argument: {:?}", name),
-        &syn::FnArg::Inferred(_) => panic!("inferred argument: {:?}", name),
+        &syn::FnArg::Ignored(ref ignored) => Err(syn::Error::new_spanned(
+            ignored,
+            format!("ignored argument: {:?}", name),
+        )),
+        &syn::FnArg::Inferred(ref pat) => Err(syn::Error::new_spanned(
+            pat,
+            format!("inferred argument: {:?}", name),
+        )),
     }
 }
 
 /// Extracts the data from the #[pyfn(...)] attribute of a function
 fn extract_pyfn_attrs(
     attrs: &mut Vec<syn::Attribute>,
-) -> Option<(syn::Ident, syn::Ident, Vec<args::Argument>)> {
+) -> syn::Result<Option<(syn::Ident, syn::Ident, Vec<args::Argument>)>> {
     let mut new_attrs = Vec::new();
     let mut fnname = None;
     let mut modname = None;
@@ -118,21 +225,36 @@ fn extract_pyfn_attrs(
                         syn::NestedMeta::Meta(syn::Meta::Word(ref ident)) => {
                             modname = Some(ident.clone())
                         }
-                        _ => panic!("The first parameter of pyfn must be a MetaItem"),
+                        ref other => {
+                            return Err(syn::Error::new_spanned(
+                                other,
+                                "The first parameter of pyfn must be a MetaItem",
+                            ));
+                        }
                     }
                     // read Python fonction name
                     match meta[1] {
                         syn::NestedMeta::Literal(syn::Lit::Str(ref lits)) => {
-                            fnname = Some(syn::parse_str(&lits.value()).unwrap());
+                            fnname = Some(syn::parse_str(&lits.value()).map_err(|_| {
+                                syn::Error::new_spanned(
+                                    lits,
+                                    "The second parameter of pyfn must be a valid identifier",
+                                )
+                            })?);
+                        }
+                        ref other => {
+                            return Err(syn::Error::new_spanned(
+                                other,
+                                "The second parameter of pyfn must be a Literal",
+                            ));
                         }
-                        _ => panic!("The second parameter of pyfn must be a Literal"),
                     }
                     // Read additional arguments
                     if list.nested.len() >= 3 {
                         fn_attrs = args::parse_arguments(&meta[2..meta.len()]);
                     }
                 } else {
-                    panic!("can not parse 'pyfn' params {:?}", attr);
+                    return Err(syn::Error::new_spanned(attr, "can not parse 'pyfn' params"));
                 }
             }
             _ => new_attrs.push(attr.clone()),
@@ -140,7 +262,68 @@ fn extract_pyfn_attrs(
     }
 
     *attrs = new_attrs;
-    Some((modname?, fnname?, fn_attrs))
+    Ok(match (modname, fnname) {
+        (Some(modname), Some(fnname)) => Some((modname, fnname, fn_attrs)),
+        _ => None,
+    })
+}
+
+/// Prepends a `name(arg1, arg2=..., /)` `__text_signature__` built from `spec`'s arguments to a
+/// doc string literal.
+fn add_text_signature(
+    python_name: &syn::Ident,
+    spec: &method::FnSpec<'_>,
+    doc: syn::Lit,
+) -> syn::Lit {
+    let signature = text_signature(spec);
+
+    match doc {
+        syn::Lit::Str(lit) => {
+            let combined = format!("{}{}\n--\n\n{}", python_name, signature, lit.value());
+            syn::Lit::Str(syn::LitStr::new(&combined, lit.span()))
+        }
+        other => other,
+    }
+}
+
+/// Renders the `(arg1, arg2=default, /)` parameter list of a `__text_signature__` from the
+/// arguments already collected in `add_fn_to_module`.
+fn text_signature(spec: &method::FnSpec<'_>) -> String {
+    let varargs_sink = find_varargs_sink(&spec.attrs).ok().flatten();
+    let kwargs_sink = find_kwargs_sink(&spec.attrs).ok().flatten();
+
+    let mut positional = Vec::new();
+    let mut sinks = Vec::new();
+
+    for arg in spec.args.iter().filter(|arg| !arg.py) {
+        if Some(arg.name) == varargs_sink {
+            sinks.push(format!("*{}", arg.name));
+        } else if Some(arg.name) == kwargs_sink {
+            sinks.push(format!("**{}", arg.name));
+        } else {
+            let default = spec.attrs.iter().find_map(|attr| match attr {
+                args::Argument::Arg(ref ident, Some(ref default)) if ident == arg.name => {
+                    Some(default.clone())
+                }
+                _ => None,
+            });
+
+            positional.push(match default {
+                Some(default) => format!("{}={}", arg.name, default),
+                None if arg.optional.is_some() => format!("{}=None", arg.name),
+                None => arg.name.to_string(),
+            });
+        }
+    }
+
+    // #[pyfn]/#[pyfunction] arguments are always bound positionally, so once there is at
+    // least one plain parameter the whole list is marked positional-only.
+    if !positional.is_empty() {
+        positional.push("/".to_string());
+    }
+
+    positional.extend(sinks);
+    format!("({})", positional.join(", "))
 }
 
 /// Coordinates the naming of a the add-function-to-python-module function
@@ -162,11 +345,11 @@ pub fn add_fn_to_module(
     func: &syn::ItemFn,
     python_name: &syn::Ident,
     pyfn_attrs: Vec<args::Argument>,
-) -> TokenStream {
+) -> syn::Result<TokenStream> {
     let mut arguments = Vec::new();
 
     for input in func.decl.inputs.iter() {
-        if let Some(fn_arg) = wrap_fn_argument(input, &func.ident) {
+        if let Some(fn_arg) = wrap_fn_argument(input, &func.ident)? {
             arguments.push(fn_arg);
         }
     }
@@ -182,8 +365,8 @@ pub fn add_fn_to_module(
 
     let function_wrapper_ident = function_wrapper_ident(&func.ident);
 
-    let wrapper = function_c_wrapper(&func.ident, &spec);
-    let doc = utils::get_doc(&func.attrs, true);
+    let wrapper = function_c_wrapper(&func.ident, &spec)?;
+    let doc = add_text_signature(python_name, &spec, utils::get_doc(&func.attrs, true));
 
     let tokens = quote! {
         fn #function_wrapper_ident(py: ::pyo3::Python) -> ::pyo3::PyObject {
@@ -210,31 +393,150 @@ pub fn add_fn_to_module(
         }
     };
 
-    tokens
+    Ok(tokens)
+}
+
+/// Returns `true` if `ty` is (a reference to) `name`, e.g. `PyTuple` for a `&PyTuple` parameter.
+fn type_is(ty: &syn::Type, name: &str) -> bool {
+    match ty {
+        syn::Type::Reference(ref reference) => type_is(&reference.elem, name),
+        syn::Type::Path(ref typath) => typath
+            .path
+            .segments
+            .last()
+            .map(|seg| seg.value().ident == name)
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Finds the parameter flagged as the `*args` sink by `name = "*"` in `#[pyfn(...)]`, erroring
+/// if more than one parameter is flagged.
+fn find_varargs_sink(attrs: &[args::Argument]) -> syn::Result<Option<&syn::Ident>> {
+    find_sink(attrs, "*", |attr| match attr {
+        args::Argument::VarArgs(ref ident) => Some(ident),
+        _ => None,
+    })
+}
+
+/// Finds the parameter flagged as the `**kwargs` sink by `name = "**"` in `#[pyfn(...)]`,
+/// erroring if more than one parameter is flagged.
+fn find_kwargs_sink(attrs: &[args::Argument]) -> syn::Result<Option<&syn::Ident>> {
+    find_sink(attrs, "**", |attr| match attr {
+        args::Argument::KeywordArgs(ref ident) => Some(ident),
+        _ => None,
+    })
+}
+
+fn find_sink<'a>(
+    attrs: &'a [args::Argument],
+    sigil: &str,
+    extract: impl Fn(&'a args::Argument) -> Option<&'a syn::Ident>,
+) -> syn::Result<Option<&'a syn::Ident>> {
+    let mut found = None;
+    for attr in attrs {
+        if let Some(ident) = extract(attr) {
+            if found.is_some() {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    format!("only one `{}` passthrough parameter is allowed per #[pyfn]", sigil),
+                ));
+            }
+            found = Some(ident);
+        }
+    }
+    Ok(found)
 }
 
 /// Generate static function wrapper (PyCFunction, PyCFunctionWithKeywords)
-fn function_c_wrapper(name: &syn::Ident, spec: &method::FnSpec<'_>) -> TokenStream {
-    let names: Vec<syn::Ident> = spec
-        .args
-        .iter()
-        .enumerate()
-        .map(|item| {
-            if item.1.py {
-                syn::Ident::new("_py", Span::call_site())
+fn function_c_wrapper(name: &syn::Ident, spec: &method::FnSpec<'_>) -> syn::Result<TokenStream> {
+    // `*args`/`**kwargs` passthrough parameters must opt in via `name = "*"`/`name = "**"` in
+    // `#[pyfn(...)]` (the same place that flags defaults), rather than being inferred from a
+    // `&PyTuple`/`&PyDict` type alone - otherwise an ordinary `&PyDict` argument would be
+    // silently rebound to the raw kwargs dict instead of the value the caller passed for it.
+    let varargs_sink = find_varargs_sink(&spec.attrs)?;
+    let kwargs_sink = find_kwargs_sink(&spec.attrs)?;
+
+    let body_to_result = if varargs_sink.is_some() || kwargs_sink.is_some() {
+        // The normal extraction path (`py_method::impl_arg_params`) is skipped entirely for
+        // passthrough functions, so it cannot also bind non-sink arguments.
+        for arg in spec.args.iter().filter(|arg| !arg.py) {
+            if Some(arg.name) == varargs_sink {
+                if !type_is(arg.ty, "PyTuple") {
+                    return Err(syn::Error::new_spanned(
+                        arg.ty,
+                        "a `*args` passthrough parameter must be typed `&PyTuple`",
+                    ));
+                }
+            } else if Some(arg.name) == kwargs_sink {
+                if !type_is(arg.ty, "PyDict") {
+                    return Err(syn::Error::new_spanned(
+                        arg.ty,
+                        "a `**kwargs` passthrough parameter must be typed `&PyDict`",
+                    ));
+                }
             } else {
-                syn::Ident::new(&format!("arg{}", item.0), Span::call_site())
+                return Err(syn::Error::new_spanned(
+                    arg.ty,
+                    "cannot mix a `*args`/`**kwargs` passthrough parameter with normally-bound \
+                     arguments in the same #[pyfn]",
+                ));
             }
-        })
-        .collect();
-    let cb = quote! {
-        ::pyo3::ReturnTypeIntoPyResult::return_type_into_py_result(#name(#(#names),*))
-    };
+        }
 
-    let body = py_method::impl_arg_params(spec, cb);
-    let body_to_result = py_method::body_to_result(&body, spec);
+        let names: Vec<syn::Ident> = spec
+            .args
+            .iter()
+            .map(|arg| {
+                if arg.py {
+                    syn::Ident::new("_py", Span::call_site())
+                } else if Some(arg.name) == varargs_sink {
+                    syn::Ident::new("_args", Span::call_site())
+                } else {
+                    syn::Ident::new("_kwargs", Span::call_site())
+                }
+            })
+            .collect();
 
-    quote! {
+        // `_kwargs` is `Option<&PyDict>` since CPython may pass a null kwargs pointer, but a
+        // `&PyDict` sink parameter expects the dict itself, so substitute an empty dict when
+        // none was passed.
+        let unwrap_kwargs = if kwargs_sink.is_some() {
+            quote! {
+                let _kwargs = _kwargs.unwrap_or_else(|| ::pyo3::types::PyDict::new(_py));
+            }
+        } else {
+            quote! {}
+        };
+
+        quote! {
+            #unwrap_kwargs
+            let _result = ::pyo3::ReturnTypeIntoPyResult::return_type_into_py_result(
+                #name(#(#names),*)
+            );
+        }
+    } else {
+        let names: Vec<syn::Ident> = spec
+            .args
+            .iter()
+            .enumerate()
+            .map(|item| {
+                if item.1.py {
+                    syn::Ident::new("_py", Span::call_site())
+                } else {
+                    syn::Ident::new(&format!("arg{}", item.0), Span::call_site())
+                }
+            })
+            .collect();
+        let cb = quote! {
+            ::pyo3::ReturnTypeIntoPyResult::return_type_into_py_result(#name(#(#names),*))
+        };
+
+        let body = py_method::impl_arg_params(spec, cb);
+        py_method::body_to_result(&body, spec)
+    };
+
+    Ok(quote! {
         unsafe extern "C" fn __wrap(
             _slf: *mut ::pyo3::ffi::PyObject,
             _args: *mut ::pyo3::ffi::PyObject,
@@ -251,5 +553,132 @@ fn function_c_wrapper(name: &syn::Ident, spec: &method::FnSpec<'_>) -> TokenStre
             ::pyo3::callback::cb_convert(
                 ::pyo3::callback::PyObjectCallbackConverter, _py, _result)
         }
+    })
+}
+
+/// Reads an explicit `= N` enum discriminant as an `i64`.
+fn explicit_discriminant(expr: &syn::Expr) -> syn::Result<i64> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(ref lit),
+            ..
+        }) => Ok(lit.value() as i64),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            ref expr,
+            ..
+        }) => explicit_discriminant(expr).map(|value| -value),
+        _ => Err(syn::Error::new_spanned(
+            expr,
+            "#[pyclass] enum discriminants must be an integer literal",
+        )),
     }
 }
+
+/// Generates the `#[pyclass]` implementation for a fieldless (C-like) Rust `enum`.
+pub fn process_pyclass_enum(enum_: &syn::ItemEnum) -> syn::Result<TokenStream> {
+    let cls = &enum_.ident;
+
+    let mut variants = Vec::new();
+    let mut discriminants = Vec::new();
+    let mut next_discriminant: i64 = 0;
+    for variant in enum_.variants.iter() {
+        match variant.fields {
+            syn::Fields::Unit => {}
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "#[pyclass] enums may not carry data; only fieldless (C-like) enums can be \
+                     converted to Python classes",
+                ));
+            }
+        }
+
+        // Follow normal Rust enum discriminant rules: an explicit `= N` both becomes this
+        // variant's value and resets the implicit count for the variants that follow it.
+        let discriminant = match &variant.discriminant {
+            Some((_, expr)) => explicit_discriminant(expr)?,
+            None => next_discriminant,
+        };
+        next_discriminant = discriminant + 1;
+
+        variants.push(&variant.ident);
+        discriminants.push(discriminant as isize);
+    }
+
+    // Each variant becomes a `#[classattr]`, the mechanism that actually populates the
+    // type's `tp_dict` at class creation, so e.g. `Color.Red` resolves from Python.
+    let variant_classattrs = variants.iter().map(|variant| {
+        quote! {
+            #[classattr]
+            fn #variant() -> #cls {
+                #cls::#variant
+            }
+        }
+    });
+
+    let repr_arms = variants.iter().map(|variant| {
+        let repr = format!("{}.{}", cls, variant);
+        quote! { #cls::#variant => #repr, }
+    });
+
+    let discriminant_arms =
+        variants
+            .iter()
+            .zip(discriminants.iter())
+            .map(|(variant, discriminant)| {
+                quote! { #cls::#variant => #discriminant, }
+            });
+
+    Ok(quote! {
+        impl #cls {
+            fn __pyo3_discriminant(&self) -> isize {
+                match self {
+                    #(#discriminant_arms)*
+                }
+            }
+        }
+
+        #[::pyo3::pymethods]
+        impl #cls {
+            #(#variant_classattrs)*
+        }
+
+        #[::pyo3::proto_methods::pyproto]
+        impl ::pyo3::class::basic::PyObjectProtocol for #cls {
+            fn __repr__(&self) -> ::pyo3::PyResult<&'static str> {
+                Ok(match self {
+                    #(#repr_arms)*
+                })
+            }
+
+            fn __hash__(&self) -> ::pyo3::PyResult<isize> {
+                Ok(self.__pyo3_discriminant())
+            }
+
+            fn __richcmp__(
+                &self,
+                other: ::pyo3::PyRef<#cls>,
+                op: ::pyo3::class::basic::CompareOp,
+            ) -> ::pyo3::PyResult<bool> {
+                let this = self.__pyo3_discriminant();
+                let other = other.__pyo3_discriminant();
+                Ok(match op {
+                    ::pyo3::class::basic::CompareOp::Eq => this == other,
+                    ::pyo3::class::basic::CompareOp::Ne => this != other,
+                    ::pyo3::class::basic::CompareOp::Lt => this < other,
+                    ::pyo3::class::basic::CompareOp::Le => this <= other,
+                    ::pyo3::class::basic::CompareOp::Gt => this > other,
+                    ::pyo3::class::basic::CompareOp::Ge => this >= other,
+                })
+            }
+        }
+
+        #[::pyo3::proto_methods::pyproto]
+        impl ::pyo3::class::number::PyNumberProtocol for #cls {
+            fn __int__(&self) -> ::pyo3::PyResult<isize> {
+                Ok(self.__pyo3_discriminant())
+            }
+        }
+    })
+}
@@ -12,4 +12,10 @@ enum NotDrivedClass {
     y,
 }
 
+#[pyclass]
+enum NotFieldlessEnum {
+    x(i32),
+    y,
+}
+
 fn main() {}
@@ -0,0 +1,24 @@
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+#[pymodule]
+fn module_with_fn(_py: Python, m: &PyModule) -> PyResult<()> {
+    #[pyfn(m, "mixed", args = "*")]
+    fn mixed(args: &PyTuple, extra: i32) -> PyResult<i32> {
+        Ok(extra)
+    }
+
+    #[pyfn(m, "double_varargs", first = "*", second = "*")]
+    fn double_varargs(first: &PyTuple, second: &PyTuple) -> PyResult<()> {
+        Ok(())
+    }
+
+    #[pyfn(m, "wrong_type", n = "*")]
+    fn wrong_type(n: i32) -> PyResult<i32> {
+        Ok(n)
+    }
+
+    Ok(())
+}
+
+fn main() {}
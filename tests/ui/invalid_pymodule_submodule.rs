@@ -0,0 +1,13 @@
+use pyo3::prelude::*;
+
+#[pymodule]
+fn parent_module(_py: Python, m: &PyModule) -> PyResult<()> {
+    #[pymodule()]
+    fn child_module(_py: Python, _m: &PyModule) -> PyResult<()> {
+        Ok(())
+    }
+
+    Ok(())
+}
+
+fn main() {}